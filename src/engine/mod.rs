@@ -1,12 +1,16 @@
 mod piece;
 
+use std::fmt;
 use std::ops::{Index, IndexMut};
+use std::time::Duration;
 
 use cgmath::{Point2, Vector2};
-use piece::{Kind as PieceKind, Piece};
-use rand::{rng, rngs::ThreadRng, seq::SliceRandom};
+use piece::Piece;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
 
-type Coord = Point2<usize>;
+pub use piece::{Kind, Rotation, RotationDir};
+
+pub type Coord = Point2<usize>;
 type Offset = Vector2<isize>;
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -24,27 +28,287 @@ impl Move {
     }
 }
 
+/// Why an intent method (`move_cursor`, `rotate`, `hard_drop`, `hold`,
+/// `update`) could not be carried out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EngineError {
+    /// There's no piece currently in play.
+    NoCursor,
+    /// The wall or the stack blocks the attempted move.
+    Blocked,
+    /// Hold has already been used for the piece in play.
+    HoldUsed,
+    /// The stack has topped out; no further moves are possible.
+    GameOver,
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::NoCursor => "no piece is currently in play",
+            Self::Blocked => "the move is blocked by the wall or the stack",
+            Self::HoldUsed => "hold has already been used for this piece",
+            Self::GameOver => "the game is already over",
+        })
+    }
+}
+
+impl std::error::Error for EngineError {}
+
+/// How long the cursor can rest on the stack before it locks in place.
+const LOCK_DELAY: Duration = Duration::from_millis(500);
+
+/// How many times the lock delay may be reset by a move/rotate before it
+/// locks regardless, so a piece can't be slid forever.
+const MAX_LOCK_RESETS: u32 = 15;
+
+/// Gravity speed multiplier while soft-dropping.
+const SOFT_DROP_FACTOR: u32 = 20;
+
+/// How many upcoming pieces the bag is kept topped up with, for the preview.
+const PREVIEW_LEN: usize = 5;
+
+/// The narrowest board that can fit every piece (the I piece needs 4 columns).
+const MIN_WIDTH: usize = 4;
+
+/// The shortest buffer that gives every piece at least one hidden row to
+/// spawn into above the visible field.
+const MIN_BUFFER: usize = 1;
+
 pub struct Engine {
     matrix: Matrix,
-    bag: Vec<PieceKind>,
-    rng: ThreadRng,
+    bag: Vec<Kind>,
+    rng: StdRng,
     cursor: Option<Piece>,
+    held: Option<Kind>,
+    hold_used: bool,
+    stats: Stats,
+    gravity_accum: Duration,
+    lock_timer: Option<Duration>,
+    lock_resets: u32,
+    game_over: bool,
 }
 
 impl Engine {
     pub fn new() -> Self {
+        Self::with_seed(rand::random())
+    }
+
+    /// Builds an engine whose RNG is seeded deterministically, so the same
+    /// seed always produces the same bag order — for tests and replays.
+    pub fn with_seed(seed: u64) -> Self {
+        Self::build(Dimensions::STANDARD, StdRng::seed_from_u64(seed))
+    }
+
+    /// Builds an engine for a non-standard field, e.g. a wider or taller
+    /// variant. `buffer` is how many hidden rows sit above `height`, for
+    /// pieces to spawn into before they're visible.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `width` is too narrow to fit every piece, `height` is 0, or
+    /// `buffer` is too short to spawn a piece into.
+    pub fn with_dimensions(width: usize, height: usize, buffer: usize) -> Self {
+        Self::with_dimensions_and_seed(width, height, buffer, rand::random())
+    }
+
+    /// Like [`Engine::with_dimensions`], but with a deterministic RNG seed,
+    /// so non-standard boards can be driven deterministically for tests and
+    /// replays too.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `width` is too narrow to fit every piece, `height` is 0, or
+    /// `buffer` is too short to spawn a piece into.
+    pub fn with_dimensions_and_seed(width: usize, height: usize, buffer: usize, seed: u64) -> Self {
+        assert!(
+            width >= MIN_WIDTH,
+            "board width must be at least {MIN_WIDTH}, got {width}"
+        );
+        assert!(height >= 1, "board must have at least one visible row");
+        assert!(
+            buffer >= MIN_BUFFER,
+            "board buffer must be at least {MIN_BUFFER} row(s) for a piece to spawn into, got {buffer}"
+        );
+        Self::build(
+            Dimensions {
+                width,
+                visible_height: height,
+                buffer_height: buffer,
+            },
+            StdRng::seed_from_u64(seed),
+        )
+    }
+
+    fn build(dimensions: Dimensions, rng: StdRng) -> Self {
         Engine {
-            matrix: Matrix::blank(),
+            matrix: Matrix::blank(dimensions),
             bag: Vec::new(),
-            rng: rng(),
+            rng,
             cursor: None,
+            held: None,
+            hold_used: false,
+            stats: Stats::new(),
+            gravity_accum: Duration::ZERO,
+            lock_timer: None,
+            lock_resets: 0,
+            game_over: false,
+        }
+    }
+
+    /// A read-only view of every cell on the board, for rendering.
+    pub fn cells(&self) -> impl Iterator<Item = (Coord, Option<Color>)> + '_ {
+        let width = self.matrix.dimensions.width;
+        let height = self.matrix.dimensions.height();
+        (0..height).flat_map(move |y| {
+            (0..width).map(move |x| {
+                let coord = Coord::new(x, y);
+                (coord, self.matrix[coord])
+            })
+        })
+    }
+
+    /// Where the falling piece's cells are, for rendering the cursor.
+    pub fn cursor_cells(&self) -> Option<[Coord; Piece::CELL_COUNT]> {
+        self.cursor?.cells(self.matrix.dimensions.width)
+    }
+
+    /// Swaps the current cursor with the held piece, spawning a fresh one
+    /// the first time a piece is held. Only one hold is allowed per piece
+    /// in play; it's re-armed once that piece locks.
+    pub fn hold(&mut self) -> Result<(), EngineError> {
+        if self.game_over {
+            return Err(EngineError::GameOver);
+        }
+        if self.hold_used {
+            return Err(EngineError::HoldUsed);
+        }
+        let Some(cursor) = self.cursor.take() else {
+            return Err(EngineError::NoCursor);
+        };
+        let next = match self.held.replace(cursor.kind) {
+            Some(kind) => Piece::spawn(kind, &self.matrix.dimensions),
+            None => self.drawn_piece(),
+        };
+        self.install_cursor(next);
+        self.hold_used = true;
+        self.lock_timer = None;
+        self.lock_resets = 0;
+        Ok(())
+    }
+
+    /// Peeks the next `n` upcoming pieces without consuming them, topping up
+    /// the look-ahead buffer from fresh shuffled bags as needed so the
+    /// preview never runs dry mid-bag.
+    pub fn next_queue(&mut self, n: usize) -> Vec<Kind> {
+        self.ensure_lookahead(n);
+        self.bag.iter().take(n).copied().collect()
+    }
+
+    /// Where the current cursor would land if dropped now, for drawing a
+    /// landing shadow.
+    pub fn ghost_cells(&self) -> Option<[Coord; Piece::CELL_COUNT]> {
+        let mut piece = self.cursor?;
+        while let Some(lower) = self.piece_ticked_down(&piece) {
+            piece = lower;
+        }
+        piece.cells(self.matrix.dimensions.width)
+    }
+
+    /// Advances the game clock by `dt`, applying gravity, lock delay, and
+    /// spawning the next piece when there is none in play.
+    pub fn update(&mut self, dt: Duration, soft_drop: bool) -> Result<(), EngineError> {
+        if self.game_over {
+            return Err(EngineError::GameOver);
+        }
+
+        if self.cursor.is_none() {
+            self.spawn_next();
+            if self.game_over {
+                return Err(EngineError::GameOver);
+            }
+        }
+
+        let interval = self.gravity_interval(soft_drop);
+        self.gravity_accum += dt;
+        while self.gravity_accum >= interval && !self.cursor_has_hit_bottom() {
+            self.gravity_accum -= interval;
+            self.try_tick_down();
+        }
+
+        if self.cursor_has_hit_bottom() {
+            let timer = self.lock_timer.get_or_insert(Duration::ZERO);
+            *timer += dt;
+            if *timer >= LOCK_DELAY {
+                self.place_cursor();
+                self.lock_timer = None;
+                self.lock_resets = 0;
+            }
+        } else {
+            self.lock_timer = None;
+            self.lock_resets = 0;
+        }
+
+        Ok(())
+    }
+
+    fn gravity_interval(&self, soft_drop: bool) -> Duration {
+        let level = self.stats.level as u32;
+        let ms = 1000u32.saturating_sub((level - 1) * 50).max(50);
+        let ms = if soft_drop { ms / SOFT_DROP_FACTOR } else { ms };
+        Duration::from_millis(ms.max(1) as u64)
+    }
+
+    /// Resets the lock delay after a successful move/rotate, as long as the
+    /// reset budget for the current piece isn't exhausted.
+    fn refresh_lock_delay(&mut self) {
+        if self.lock_timer.is_some() && self.lock_resets < MAX_LOCK_RESETS {
+            self.lock_timer = Some(Duration::ZERO);
+            self.lock_resets += 1;
+        }
+    }
+
+    fn spawn_next(&mut self) {
+        let piece = self.drawn_piece();
+        self.install_cursor(piece);
+    }
+
+    /// Installs `piece` as the cursor, triggering game over if its spawn
+    /// cells are already blocked by the stack (a "block out").
+    fn install_cursor(&mut self, piece: Piece) {
+        if self.matrix.is_clipping(&piece) {
+            self.game_over = true;
+        }
+        self.cursor = Some(piece);
+    }
+
+    /// Draws the next piece from the front of the bag, topping up the
+    /// look-ahead buffer from fresh shuffled bags as needed.
+    fn drawn_piece(&mut self) -> Piece {
+        self.ensure_lookahead(PREVIEW_LEN + 1);
+        Piece::spawn(self.bag.remove(0), &self.matrix.dimensions)
+    }
+
+    fn ensure_lookahead(&mut self, minimum: usize) {
+        while self.bag.len() < minimum {
+            self.refill_bag();
         }
     }
 
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Whether the stack has topped out and no further moves are possible.
+    pub fn game_over(&self) -> bool {
+        self.game_over
+    }
+
+    /// Appends a freshly shuffled 7-bag to the end of the draw queue.
     fn refill_bag(&mut self) {
-        debug_assert!(self.bag.is_empty());
-        self.bag.extend(PieceKind::ALL.as_slice());
-        self.bag.shuffle(&mut self.rng);
+        let start = self.bag.len();
+        self.bag.extend(Kind::ALL.as_slice());
+        self.bag[start..].shuffle(&mut self.rng);
     }
 
     fn place_cursor(&mut self) {
@@ -53,35 +317,68 @@ impl Engine {
             .take()
             .expect("Called 'place_cursor' without a cursor");
         debug_assert!(
-            !self.matrix.is_placeable(&cursor),
+            self.matrix.is_placeable(&cursor),
             "Tried to place cursor in an invalid location {:?}",
             cursor
         );
         let color = cursor.kind.color();
-        for coord in cursor.cells().unwrap() {
+        let cells = cursor.cells(self.matrix.dimensions.width).unwrap();
+        for coord in cells {
             self.matrix[coord] = Some(color);
         }
+        if cells.iter().all(|&coord| self.matrix.is_in_buffer(coord)) {
+            self.game_over = true;
+        }
+        let cleared = self.matrix.clear_full_lines();
+        self.stats.register_clear(cleared);
+        self.hold_used = false;
     }
 
-    fn move_cursor(&mut self, r#move: Move) -> Result<(), ()> {
+    pub fn move_cursor(&mut self, r#move: Move) -> Result<(), EngineError> {
+        if self.game_over {
+            return Err(EngineError::GameOver);
+        }
         let Some(cursor) = self.cursor.as_mut() else {
             return Ok(());
         };
         let new = cursor.moved_by(r#move.offset());
         if self.matrix.is_clipping(&new) {
-            return Err(());
+            return Err(EngineError::Blocked);
         };
         self.cursor = Some(new);
+        self.refresh_lock_delay();
         Ok(())
     }
 
+    pub fn rotate(&mut self, dir: RotationDir) -> Result<(), EngineError> {
+        if self.game_over {
+            return Err(EngineError::GameOver);
+        }
+        let Some(cursor) = self.cursor else {
+            return Ok(());
+        };
+        let rotated = cursor.rotated(dir);
+        for offset in cursor.kicks(rotated.rotation) {
+            let kicked = rotated.moved_by(offset);
+            if !self.matrix.is_clipping(&kicked) {
+                self.cursor = Some(kicked);
+                self.refresh_lock_delay();
+                return Ok(());
+            }
+        }
+        Err(EngineError::Blocked)
+    }
+
     fn try_tick_down(&mut self) {
         self.cursor = Some(self.ticked_down_cursor().unwrap());
     }
 
     fn ticked_down_cursor(&self) -> Option<Piece> {
-        let cursor = self.cursor?;
-        let new = cursor.moved_by(Offset::new(0, -1));
+        self.piece_ticked_down(&self.cursor?)
+    }
+
+    fn piece_ticked_down(&self, piece: &Piece) -> Option<Piece> {
+        let new = piece.moved_by(Offset::new(0, -1));
         (!self.matrix.is_clipping(&new)).then_some(new)
     }
 
@@ -89,11 +386,56 @@ impl Engine {
         self.cursor.is_some() && self.ticked_down_cursor().is_none()
     }
 
-    fn hard_drop(&mut self) {
+    pub fn hard_drop(&mut self) -> Result<(), EngineError> {
+        if self.game_over {
+            return Err(EngineError::GameOver);
+        }
+        if self.cursor.is_none() {
+            return Err(EngineError::NoCursor);
+        }
         while let Some(new) = self.ticked_down_cursor() {
             self.cursor = Some(new);
         }
         self.place_cursor();
+        Ok(())
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Points awarded for clearing 1/2/3/4 lines at once, before the level multiplier.
+const LINE_CLEAR_SCORES: [usize; 5] = [0, 100, 300, 500, 800];
+
+/// How many lines clear before the level advances.
+const LINES_PER_LEVEL: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stats {
+    pub lines: usize,
+    pub score: usize,
+    pub level: usize,
+}
+
+impl Stats {
+    fn new() -> Self {
+        Stats {
+            lines: 0,
+            score: 0,
+            level: 1,
+        }
+    }
+
+    fn register_clear(&mut self, cleared: usize) {
+        if cleared == 0 {
+            return;
+        }
+        self.score += LINE_CLEAR_SCORES[cleared] * self.level;
+        self.lines += cleared;
+        self.level = 1 + self.lines / LINES_PER_LEVEL;
     }
 }
 
@@ -108,59 +450,207 @@ pub enum Color {
     Red,
 }
 
-struct Matrix([Option<Color>; Self::WIDTH * Self::HEIGHT]);
+/// A board's size: how many columns wide, how many visible rows, and how
+/// many hidden rows above those for pieces to spawn into before they're
+/// in play.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct Dimensions {
+    pub width: usize,
+    pub visible_height: usize,
+    pub buffer_height: usize,
+}
+
+impl Dimensions {
+    const STANDARD: Self = Self {
+        width: 10,
+        visible_height: 20,
+        buffer_height: 4,
+    };
+
+    fn height(&self) -> usize {
+        self.visible_height + self.buffer_height
+    }
+
+    fn size(&self) -> usize {
+        self.width * self.height()
+    }
+}
+
+struct Matrix {
+    dimensions: Dimensions,
+    cells: Vec<Option<Color>>,
+}
 
 impl Matrix {
-    const WIDTH: usize = 10;
-    const HEIGHT: usize = 20;
-    const SIZE: usize = Self::WIDTH * Self::HEIGHT;
+    fn blank(dimensions: Dimensions) -> Self {
+        Self {
+            cells: vec![None; dimensions.size()],
+            dimensions,
+        }
+    }
 
-    fn blank() -> Self {
-        Self([None; Self::SIZE])
+    fn in_matrix(&self, coord: Coord) -> bool {
+        self.valid_coord(coord) && coord.y < self.dimensions.height()
     }
 
-    fn in_matrix(coord: Coord) -> bool {
-        Self::valid_coord(coord) && coord.y < Self::HEIGHT
+    fn valid_coord(&self, coord: Coord) -> bool {
+        coord.x < self.dimensions.width
     }
 
-    fn valid_coord(coord: Coord) -> bool {
-        coord.x < Self::WIDTH
+    /// Whether `coord` is in the hidden buffer zone above the visible field.
+    fn is_in_buffer(&self, coord: Coord) -> bool {
+        coord.y >= self.dimensions.visible_height
     }
 
-    fn index(Coord { x, y }: Coord) -> usize {
-        y * Self::WIDTH + x
+    fn index(&self, Coord { x, y }: Coord) -> usize {
+        y * self.dimensions.width + x
     }
 
     fn is_placeable(&self, piece: &Piece) -> bool {
-        let Some(cells) = piece.cells() else {
+        let Some(cells) = piece.cells(self.dimensions.width) else {
             return false;
         };
         cells
             .into_iter()
-            .all(|coord| Matrix::in_matrix(coord) && self[coord].is_none())
+            .all(|coord| self.in_matrix(coord) && self[coord].is_none())
     }
 
     fn is_clipping(&self, piece: &Piece) -> bool {
-        let Some(cells) = piece.cells() else {
+        let Some(cells) = piece.cells(self.dimensions.width) else {
             return true;
         };
         cells
             .into_iter()
-            .any(|coord| !Matrix::in_matrix(coord) || self[coord].is_some())
+            .any(|coord| !self.in_matrix(coord) || self[coord].is_some())
+    }
+
+    /// Removes every full row, compacting the rows above it downward, and
+    /// returns how many lines were cleared.
+    fn clear_full_lines(&mut self) -> usize {
+        let width = self.dimensions.width;
+        let height = self.dimensions.height();
+        let full_rows: Vec<usize> = (0..height)
+            .filter(|&y| (0..width).all(|x| self[Coord::new(x, y)].is_some()))
+            .collect();
+        if full_rows.is_empty() {
+            return 0;
+        }
+
+        let mut compacted = vec![None; self.dimensions.size()];
+        let mut dest_y = 0;
+        for y in 0..height {
+            if full_rows.contains(&y) {
+                continue;
+            }
+            for x in 0..width {
+                compacted[dest_y * width + x] = self[Coord::new(x, y)];
+            }
+            dest_y += 1;
+        }
+        self.cells = compacted;
+
+        full_rows.len()
     }
 }
 
 impl Index<Coord> for Matrix {
     type Output = Option<Color>;
     fn index(&self, coord: Coord) -> &Self::Output {
-        assert!(Self::in_matrix(coord));
-        &self.0[Self::index(coord)]
+        assert!(self.in_matrix(coord));
+        &self.cells[self.index(coord)]
     }
 }
 
 impl IndexMut<Coord> for Matrix {
     fn index_mut(&mut self, coord: Coord) -> &mut Self::Output {
-        assert!(Self::in_matrix(coord));
-        &mut self.0[Self::index(coord)]
+        assert!(self.in_matrix(coord));
+        let index = self.index(coord);
+        &mut self.cells[index]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fill_row(matrix: &mut Matrix, y: usize) {
+        for x in 0..matrix.dimensions.width {
+            matrix[Coord::new(x, y)] = Some(Color::Blue);
+        }
+    }
+
+    #[test]
+    fn clear_full_lines_compacts_rows_downward() {
+        let mut matrix = Matrix::blank(Dimensions::STANDARD);
+        fill_row(&mut matrix, 0);
+        fill_row(&mut matrix, 1);
+        matrix[Coord::new(3, 2)] = Some(Color::Red);
+
+        let cleared = matrix.clear_full_lines();
+
+        assert_eq!(cleared, 2);
+        assert_eq!(matrix[Coord::new(3, 0)], Some(Color::Red));
+        assert_eq!(matrix[Coord::new(0, 0)], None);
+        assert_eq!(matrix[Coord::new(0, 1)], None);
+    }
+
+    #[test]
+    fn clear_full_lines_reports_zero_when_nothing_clears() {
+        let mut matrix = Matrix::blank(Dimensions::STANDARD);
+        matrix[Coord::new(0, 0)] = Some(Color::Green);
+
+        assert_eq!(matrix.clear_full_lines(), 0);
+    }
+
+    #[test]
+    fn register_clear_scores_by_level_and_advances_level() {
+        let mut stats = Stats::new();
+
+        stats.register_clear(0);
+        assert_eq!(stats, Stats { lines: 0, score: 0, level: 1 });
+
+        stats.register_clear(4);
+        assert_eq!(stats.score, LINE_CLEAR_SCORES[4]);
+        assert_eq!(stats.lines, 4);
+        assert_eq!(stats.level, 1);
+
+        stats.register_clear(4);
+        assert_eq!(stats.lines, 8);
+        assert_eq!(stats.level, 1);
+
+        stats.register_clear(2);
+        assert_eq!(stats.lines, 10);
+        assert_eq!(stats.level, 2);
+        assert_eq!(
+            stats.score,
+            LINE_CLEAR_SCORES[4] + LINE_CLEAR_SCORES[4] + LINE_CLEAR_SCORES[2]
+        );
+    }
+
+    #[test]
+    fn locking_fully_inside_the_buffer_tops_out() {
+        let mut engine = Engine::with_dimensions_and_seed(4, 1, 4, 0);
+        engine.cursor = Some(Piece {
+            kind: Kind::O,
+            position: Offset::new(0, 2),
+            rotation: Rotation::N,
+        });
+
+        engine.place_cursor();
+
+        assert!(engine.game_over());
+    }
+
+    #[test]
+    fn spawning_into_an_occupied_cell_is_a_block_out() {
+        let mut engine = Engine::with_dimensions_and_seed(4, 1, 4, 0);
+        let piece = Piece::spawn(Kind::O, &engine.matrix.dimensions);
+        for coord in piece.cells(engine.matrix.dimensions.width).unwrap() {
+            engine.matrix[coord] = Some(Color::Blue);
+        }
+
+        engine.install_cursor(piece);
+
+        assert!(engine.game_over());
     }
 }
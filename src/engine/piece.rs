@@ -1,10 +1,8 @@
 use std::ops::{Mul, Neg};
 
-use crate::engine::{Coord, Offset};
+use crate::engine::{Color, Coord, Dimensions, Offset};
 use cgmath::{ElementWise, EuclideanSpace, Vector2, Zero};
 
-use super::Matrix;
-
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub(super) struct Piece {
     pub kind: Kind,
@@ -14,6 +12,22 @@ pub(super) struct Piece {
 
 impl Piece {
     pub const CELL_COUNT: usize = 4;
+    pub const KICK_COUNT: usize = 5;
+
+    /// A fresh piece in its spawn orientation, centered at the top of the
+    /// visible field.
+    /// Most kinds straddle the boundary between the visible field and the
+    /// buffer above it; the I piece spawns entirely in the buffer.
+    pub fn spawn(kind: Kind, dimensions: &Dimensions) -> Self {
+        Self {
+            kind,
+            rotation: Rotation::N,
+            position: Offset::new(
+                (dimensions.width / 2).saturating_sub(2) as isize,
+                dimensions.visible_height as isize - 2,
+            ),
+        }
+    }
 
     pub fn moved_by(&self, offset: Offset) -> Self {
         Self {
@@ -22,13 +36,13 @@ impl Piece {
         }
     }
 
-    pub fn cells(&self) -> Option<[Coord; Self::CELL_COUNT]> {
+    pub fn cells(&self, width: usize) -> Option<[Coord; Self::CELL_COUNT]> {
         let offsets = self.kind.cells().map(self.rotator()).map(self.trasnlator());
         let mut coords = [Coord::origin(); Self::CELL_COUNT];
         for (offset, coord_slot) in offsets.into_iter().zip(&mut coords) {
             let positive_offset = offset.cast::<usize>()?;
             let coord = Coord::from_vec(positive_offset);
-            if Matrix::valid_coord(coord) {
+            if coord.x < width {
                 *coord_slot = coord;
             } else {
                 return None;
@@ -37,6 +51,53 @@ impl Piece {
         Some(coords)
     }
 
+    pub fn rotated(&self, dir: RotationDir) -> Self {
+        Self {
+            rotation: self.rotation.rotated(dir),
+            ..*self
+        }
+    }
+
+    /// The SRS kick offsets to try, in order, when rotating this piece to `to`.
+    pub fn kicks(&self, to: Rotation) -> [Offset; Self::KICK_COUNT] {
+        let raw = match self.kind {
+            Kind::O => [(0, 0); Self::KICK_COUNT],
+            Kind::I => Self::i_kicks(self.rotation, to),
+            _ => Self::jlstz_kicks(self.rotation, to),
+        };
+        raw.map(Offset::from)
+    }
+
+    fn jlstz_kicks(from: Rotation, to: Rotation) -> [(isize, isize); Self::KICK_COUNT] {
+        use Rotation::*;
+        match (from, to) {
+            (N, E) => [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+            (E, N) => [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+            (E, S) => [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+            (S, E) => [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+            (S, W) => [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+            (W, S) => [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+            (W, N) => [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+            (N, W) => [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+            _ => unreachable!("rotation states are always one step apart"),
+        }
+    }
+
+    fn i_kicks(from: Rotation, to: Rotation) -> [(isize, isize); Self::KICK_COUNT] {
+        use Rotation::*;
+        match (from, to) {
+            (N, E) => [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
+            (E, N) => [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+            (E, S) => [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+            (S, E) => [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+            (S, W) => [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+            (W, S) => [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
+            (W, N) => [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+            (N, W) => [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+            _ => unreachable!("rotation states are always one step apart"),
+        }
+    }
+
     fn rotator(&self) -> impl Fn(Offset) -> Offset + '_ {
         |cell| match self.kind {
             Kind::O => cell,
@@ -91,6 +152,18 @@ impl Kind {
         .map(Vector2::from)
     }
 
+    pub fn color(&self) -> Color {
+        match self {
+            Self::O => Color::Yellow,
+            Self::I => Color::Cyan,
+            Self::T => Color::Pruple,
+            Self::L => Color::Orange,
+            Self::J => Color::Blue,
+            Self::S => Color::Green,
+            Self::Z => Color::Red,
+        }
+    }
+
     fn local_grid_size(&self) -> isize {
         match self {
             Self::I => 4,
@@ -118,6 +191,29 @@ impl Rotation {
     }
 }
 
+/// The direction a rotation input turns the piece.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RotationDir {
+    Cw,
+    Ccw,
+}
+
+impl Rotation {
+    fn rotated(&self, dir: RotationDir) -> Self {
+        use Rotation::*;
+        match (self, dir) {
+            (N, RotationDir::Cw) => E,
+            (E, RotationDir::Cw) => S,
+            (S, RotationDir::Cw) => W,
+            (W, RotationDir::Cw) => N,
+            (N, RotationDir::Ccw) => W,
+            (W, RotationDir::Ccw) => S,
+            (S, RotationDir::Ccw) => E,
+            (E, RotationDir::Ccw) => N,
+        }
+    }
+}
+
 impl<S> Mul<Rotation> for Vector2<S>
 where
     S: Neg<Output = S>,
@@ -145,8 +241,47 @@ mod tests {
             rotation: Rotation::W,
         };
         assert_eq!(
-            s.cells(),
+            s.cells(10),
             Some([(7, 6), (7, 7), (6, 7), (6, 8)].map(Coord::from))
         )
     }
+
+    #[test]
+    fn o_piece_never_kicks() {
+        let o = Piece {
+            kind: Kind::O,
+            position: Offset::new(4, 4),
+            rotation: Rotation::N,
+        };
+        assert_eq!(o.kicks(Rotation::E), [Offset::new(0, 0); Piece::KICK_COUNT]);
+    }
+
+    #[test]
+    fn jlstz_kicks_are_mirrored_for_opposite_rotations() {
+        let t = Piece {
+            kind: Kind::T,
+            position: Offset::new(4, 4),
+            rotation: Rotation::N,
+        };
+        let first = t.kicks(Rotation::E);
+        assert_eq!(first[0], Offset::new(0, 0));
+        assert_eq!(first[1], Offset::new(-1, 0));
+
+        let back = Piece { rotation: Rotation::E, ..t }.kicks(Rotation::N);
+        assert_eq!(back[1], Offset::new(1, 0));
+        assert_eq!(back[2], Offset::new(1, -1));
+    }
+
+    #[test]
+    fn i_piece_uses_its_own_wider_kick_table() {
+        let i = Piece {
+            kind: Kind::I,
+            position: Offset::new(4, 4),
+            rotation: Rotation::N,
+        };
+        assert_eq!(
+            i.kicks(Rotation::E),
+            [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)].map(Offset::from)
+        );
+    }
 }
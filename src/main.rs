@@ -1,8 +1,7 @@
 use engine::Engine;
 use interface::Interface;
 
-#[allow(dead_code)]
-mod engine;
+pub mod engine;
 mod interface;
 
 fn main() {